@@ -16,7 +16,9 @@ use std::collections::HashMap;
 use message;
 use message::MessageType;
 use message::ProtocolMessage;
+use oplog::LogFormat;
 use oplog::OpLog;
+use oplog::StorageMode;
 
 ///
 /// check_participant()
@@ -37,28 +39,13 @@ fn check_participant(
     num_commit: usize,
     num_abort: usize,
     coord_committed: &HashMap<u32, ProtocolMessage>,
-    participant_log: &HashMap<u32, ProtocolMessage>
+    participant_commit_map: &HashMap<u32, ProtocolMessage>,
+    participant_local_commit_map: &HashMap<u32, ProtocolMessage>,
+    participant_abort_map: &HashMap<u32, ProtocolMessage>
     ) -> bool {
 
     let mut result = true;
 
-    // Filter the participant log for Global Commits, Local Commits, and Aborted
-    let participant_commit_map: HashMap<u32, message::ProtocolMessage> =
-        participant_log.iter()
-        .filter(|e| (*e.1).mtype == MessageType::CoordinatorCommit)
-        .map(|(k,v)| (k.clone(), v.clone()))
-        .collect();
-    let participant_local_commit_map: HashMap<u32, message::ProtocolMessage> =
-        participant_log.iter()
-        .filter(|e| (*e.1).mtype == MessageType::ParticipantVoteCommit)
-        .map(|(k,v)| (k.clone(), v.clone()))
-        .collect();
-    let participant_abort_map: HashMap<u32, message::ProtocolMessage> =
-        participant_log.iter()
-        .filter(|e| (*e.1).mtype == MessageType::CoordinatorAbort)
-        .map(|(k,v)| (k.clone(), v.clone()))
-        .collect();
-
     let num_participant_commit = participant_commit_map.len();
     let num_participant_local_commit = participant_local_commit_map.len();
     let num_participant_abort = participant_abort_map.len();
@@ -116,12 +103,16 @@ fn check_participant(
 ///     num_requests: Number of requests per client
 ///     num_participants: Number of participants
 ///     log_path: Directory for client, participant, and coordinator logs
+///     log_format: Wire format the logs were written with
+///     storage_mode: Backend the logs were written with
 ///
 pub fn check_last_run(
     num_clients: u32,
     num_requests: u32,
     num_participants: u32,
-    log_path: &String) {
+    log_path: &String,
+    log_format: LogFormat,
+    storage_mode: StorageMode) {
 
         info!("Checking 2PC run:  {} requests * {} clients, {} participants",
               num_requests,
@@ -129,22 +120,20 @@ pub fn check_last_run(
               num_participants);
 
         let coord_log_path = format!("{}//{}", log_path, "coordinator.log");
-        let coord_log = OpLog::from_file(coord_log_path);
-
-        let lock = coord_log.arc();
-        let coord_map = lock.lock().unwrap();
-
-        // Filter coordinator logs for Commit and Abort
-        let committed: HashMap<u32, message::ProtocolMessage> =
-            coord_map.iter()
-            .filter(|e| (*e.1).mtype == MessageType::CoordinatorCommit)
-            .map(|(k,v)| (k.clone(), v.clone()))
-            .collect();
-        let aborted: HashMap<u32, message::ProtocolMessage> =
-            coord_map.iter()
-            .filter(|e| (*e.1).mtype == MessageType::CoordinatorAbort)
-            .map(|(k,v)| (k.clone(), v.clone()))
-            .collect();
+        let coord_log = OpLog::from_file(coord_log_path, log_format, storage_mode);
+
+        // Stream the coordinator log, keeping only the Commit and Abort records.
+        // With the LMDB backend the whole log is not held resident; the file
+        // backend has already loaded it into memory in `from_file`.
+        let mut committed: HashMap<u32, message::ProtocolMessage> = HashMap::new();
+        let mut aborted: HashMap<u32, message::ProtocolMessage> = HashMap::new();
+        coord_log.stream_records(|pm| {
+            match pm.mtype {
+                MessageType::CoordinatorCommit => { committed.insert(pm.uid, pm.clone()); },
+                MessageType::CoordinatorAbort => { aborted.insert(pm.uid, pm.clone()); },
+                _ => {},
+            }
+        });
 
         let num_commit = committed.len();
         let num_abort = aborted.len();
@@ -153,10 +142,24 @@ pub fn check_last_run(
         for pid in 0..num_participants {
             let participant_id_str = format!("participant_{}", pid);
             let participant_log_path = format!("{}//{}.log", log_path, participant_id_str);
-            let participant_oplog = OpLog::from_file(participant_log_path);
-            let participant_lock = participant_oplog.arc();
-            let participant_log = participant_lock.lock().unwrap();
-            check_participant(&participant_id_str, num_commit, num_abort, &committed, &participant_log);
+            let participant_oplog = OpLog::from_file(participant_log_path, log_format, storage_mode);
+
+            // Stream the participant log into the filtered subsets the check
+            // needs, so with the LMDB backend a log larger than RAM never has
+            // to be fully resident.
+            let mut commit_map: HashMap<u32, message::ProtocolMessage> = HashMap::new();
+            let mut local_commit_map: HashMap<u32, message::ProtocolMessage> = HashMap::new();
+            let mut abort_map: HashMap<u32, message::ProtocolMessage> = HashMap::new();
+            participant_oplog.stream_records(|pm| {
+                match pm.mtype {
+                    MessageType::CoordinatorCommit => { commit_map.insert(pm.uid, pm.clone()); },
+                    MessageType::ParticipantVoteCommit => { local_commit_map.insert(pm.uid, pm.clone()); },
+                    MessageType::CoordinatorAbort => { abort_map.insert(pm.uid, pm.clone()); },
+                    _ => {},
+                }
+            });
+            check_participant(&participant_id_str, num_commit, num_abort, &committed,
+                              &commit_map, &local_commit_map, &abort_map);
         }
     }
 
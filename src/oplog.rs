@@ -1,63 +1,309 @@
 extern crate serde;
 extern crate serde_json;
 extern crate bincode;
+extern crate flexbuffers;
+extern crate lmdb;
+extern crate crc32fast;
 
 use std::collections::HashMap;
+use std::fs;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::prelude::*;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use self::lmdb::{Cursor, Environment, Database, Transaction, WriteFlags};
 
 use message;
 
+/// Map size reserved for the LMDB environment (1 GiB). LMDB requires the
+/// maximum size be declared up front; this is comfortably larger than any log
+/// a single 2PC process produces in the exercise.
+const LMDB_MAP_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Upper bound on the number of records the LMDB backend keeps resident in the
+/// read-through cache. LMDB on disk remains the source of truth; callers that
+/// need the whole log (e.g. `checker`) stream it with `stream_records` rather
+/// than holding it in memory.
+const CACHE_CAPACITY: usize = 4096;
+
+///
+/// LogFormat
+/// Wire format used to encode each `ProtocolMessage` in the operations log.
+/// `Json` keeps the original newline-delimited text so a log can still be
+/// inspected by hand (each line is the JSON record, a tab, and its decimal
+/// CRC32); `Bincode` and `Flexbuffers` emit compact length-prefixed binary
+/// records (a little-endian `u32` length, the encoded bytes, then a
+/// little-endian `u32` CRC32 of those bytes) that shrink the log and speed up
+/// replay in `checker::check_last_run`.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Json,
+    Bincode,
+    Flexbuffers,
+}
+
+impl LogFormat {
+    ///
+    /// from_cli(s)
+    /// Parses a log format name as accepted on the command line. Unknown names
+    /// fall back to `Json` so a malformed flag degrades to the inspectable
+    /// format rather than aborting the run.
+    ///
+    pub fn from_cli(s: &str) -> LogFormat {
+        match s {
+            "bincode" => LogFormat::Bincode,
+            "flexbuffers" => LogFormat::Flexbuffers,
+            _ => LogFormat::Json,
+        }
+    }
+
+    ///
+    /// as_str()
+    /// Returns the canonical lower-case name, suitable for `TPCOptions::as_vec`.
+    ///
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LogFormat::Json => "json",
+            LogFormat::Bincode => "bincode",
+            LogFormat::Flexbuffers => "flexbuffers",
+        }
+    }
+}
+
+impl Default for LogFormat {
+    fn default() -> LogFormat {
+        LogFormat::Json
+    }
+}
+
+///
+/// StorageMode
+/// Selects how the operations log is persisted. `File` is the original
+/// append-only file backend; `Lmdb` stores each record as a committed
+/// `seqno -> ProtocolMessage` key/value transaction so a coordinator killed
+/// mid-2PC can reopen its log and continue from the last durable key.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageMode {
+    File,
+    Lmdb,
+}
+
+impl StorageMode {
+    ///
+    /// from_cli(s)
+    /// Parses a storage mode name as accepted on the command line, defaulting
+    /// to the append-only `File` backend for unknown names.
+    ///
+    pub fn from_cli(s: &str) -> StorageMode {
+        match s {
+            "lmdb" => StorageMode::Lmdb,
+            _ => StorageMode::File,
+        }
+    }
+
+    ///
+    /// as_str()
+    /// Returns the canonical lower-case name, suitable for `TPCOptions::as_vec`.
+    ///
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            StorageMode::File => "file",
+            StorageMode::Lmdb => "lmdb",
+        }
+    }
+}
+
+impl Default for StorageMode {
+    fn default() -> StorageMode {
+        StorageMode::File
+    }
+}
+
 #[derive(Debug)]
 pub struct OpLog {
     seqno: u32,
     log_arc: Arc<Mutex<HashMap<u32, message::ProtocolMessage>>>,
     path: String,
-    lf: File,
+    lf: Option<File>,
+    env: Option<Environment>,
+    db: Option<Database>,
+    format: LogFormat,
+    storage: StorageMode,
+    // Group-commit state for the file backend. Records accumulate in
+    // `write_buf` and the in-memory map, and are flushed durably once
+    // `batch_size` records are pending, on the first append after
+    // `flush_interval` has elapsed since the last flush (a zero interval
+    // disables this), or when `sync()` is called explicitly at a protocol phase
+    // boundary. No timer runs between appends.
+    write_buf: Vec<u8>,
+    pending: usize,
+    batch_size: usize,
+    flush_interval: Duration,
+    last_flush: Instant,
 }
 
 impl OpLog {
 
-    /// 
-    /// new(fpath: String)
-    /// Creates a new Operations Log at the designated file path
     ///
-    pub fn new(fpath: String) -> OpLog {
+    /// new(fpath, format, storage, batch_size, flush_interval)
+    /// Creates a new Operations Log at the designated path, encoding each record
+    /// with the given wire format. For `StorageMode::File` the path names a log
+    /// file; for `StorageMode::Lmdb` it names the environment directory. The
+    /// file backend flushes durably once `batch_size` records are buffered or,
+    /// on an append, once `flush_interval` has elapsed since the last flush (a
+    /// zero interval disables the time trigger); a `batch_size` of 1 restores
+    /// per-record durability.
+    ///
+    pub fn new(fpath: String, format: LogFormat, storage: StorageMode, batch_size: usize, flush_interval: Duration) -> OpLog {
         let l = HashMap::new();
         let lck = Mutex::new(l);
         let arc = Arc::new(lck);
+        let (lf, env, db) = match storage {
+            StorageMode::File => (Some(File::create(&fpath).unwrap()), None, None),
+            StorageMode::Lmdb => {
+                let (env, db) = open_env(&fpath);
+                // Match `File::create`'s truncation: clear any records left by a
+                // prior run so a fresh log does not inherit stale high keys.
+                {
+                    let mut txn = env.begin_rw_txn().unwrap();
+                    txn.clear_db(db).unwrap();
+                    txn.commit().unwrap();
+                }
+                (None, Some(env), Some(db))
+            },
+        };
         OpLog {
             seqno: 0,
             log_arc: arc,
-            path: fpath.to_string(),
-            lf: File::create(fpath).unwrap(),
+            path: fpath,
+            lf: lf,
+            env: env,
+            db: db,
+            format: format,
+            storage: storage,
+            write_buf: Vec::new(),
+            pending: 0,
+            batch_size: batch_size.max(1),
+            flush_interval: flush_interval,
+            last_flush: Instant::now(),
         }
     }
 
     ///
-    /// from_file(fpath: String)
+    /// from_file(fpath: String, format: LogFormat, storage: StorageMode)
     ///
-    /// Reads in and returns an existing Operations Log from the designated file
+    /// Reads in and returns an existing Operations Log from the designated path,
+    /// decoding each record with the given wire format and recovering the
+    /// highest `uid` seen into `seqno`. For `StorageMode::Lmdb` this reopens the
+    /// environment and restores `seqno` from the last committed key, so a
+    /// coordinator killed mid-2PC can continue from where it left off.
     ///
-    pub fn from_file(fpath: String) -> OpLog {
+    pub fn from_file(fpath: String, format: LogFormat, storage: StorageMode) -> OpLog {
+        match storage {
+            StorageMode::File => OpLog::from_file_backend(fpath, format),
+            StorageMode::Lmdb => OpLog::from_lmdb_backend(fpath, format),
+        }
+    }
+
+    ///
+    /// from_file_backend(fpath, format)
+    /// Recovery path for the append-only file backend.
+    ///
+    fn from_file_backend(fpath: String, format: LogFormat) -> OpLog {
         let mut seqno = 0;
         let mut l = HashMap::new();
         let scopy = fpath.clone();
         let tlf = File::open(fpath).unwrap();
+        let file_len = tlf.metadata().unwrap().len();
         let mut reader = BufReader::new(&tlf);
-        let mut line = String::new();
-        let mut len = reader.read_line(&mut line).unwrap();
-        while len > 0 {
-            let pm = message::ProtocolMessage::from_string(&line);
-            if pm.uid > seqno {
-                seqno = pm.uid;
-            }
-            l.insert(pm.uid, pm);
-            line.clear();
-            len = reader.read_line(&mut line).unwrap();
+        // Read the valid prefix only. On the first record that is short, fails
+        // to decode, or whose CRC does not match, treat the remaining bytes as
+        // a torn tail left by a crash mid-append and stop, recovering the
+        // highest valid `uid` into `seqno` rather than panicking.
+        match format {
+            LogFormat::Json => {
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    match reader.read_line(&mut line) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => {},
+                    }
+                    let record = line.trim_end_matches('\n');
+                    // A torn final line has no newline; require the tab-separated
+                    // CRC field to be present and to verify before trusting it.
+                    if !line.ends_with('\n') {
+                        break;
+                    }
+                    let split = match record.rfind('\t') {
+                        Some(idx) => idx,
+                        None => break,
+                    };
+                    let (payload, crc_str) = record.split_at(split);
+                    let expected: u32 = match crc_str[1..].parse() {
+                        Ok(v) => v,
+                        Err(_) => break,
+                    };
+                    if crc32fast::hash(payload.as_bytes()) != expected {
+                        break;
+                    }
+                    let pm: message::ProtocolMessage = match serde_json::from_slice(payload.as_bytes()) {
+                        Ok(v) => v,
+                        Err(_) => break,
+                    };
+                    if pm.uid > seqno {
+                        seqno = pm.uid;
+                    }
+                    l.insert(pm.uid, pm);
+                }
+            },
+            LogFormat::Bincode | LogFormat::Flexbuffers => {
+                // Bytes consumed so far, used to bound each record's length
+                // prefix against what actually remains on disk before we trust
+                // it enough to allocate.
+                let mut pos: u64 = 0;
+                loop {
+                    let mut length = [0u8; 4];
+                    if reader.read_exact(&mut length).is_err() {
+                        break;
+                    }
+                    pos += 4;
+                    let len = u32::from_le_bytes(length) as usize;
+                    // A garbage length in a torn tail must not drive a huge
+                    // allocation: the record needs `len` payload bytes plus a
+                    // 4-byte CRC still present in the file.
+                    if (len as u64) + 4 > file_len.saturating_sub(pos) {
+                        break;
+                    }
+                    let mut buf = vec![0u8; len];
+                    if reader.read_exact(&mut buf).is_err() {
+                        break;
+                    }
+                    pos += len as u64;
+                    let mut crc_bytes = [0u8; 4];
+                    if reader.read_exact(&mut crc_bytes).is_err() {
+                        break;
+                    }
+                    pos += 4;
+                    if crc32fast::hash(&buf) != u32::from_le_bytes(crc_bytes) {
+                        break;
+                    }
+                    let pm = match try_decode_record(format, &buf) {
+                        Some(v) => v,
+                        None => break,
+                    };
+                    if pm.uid > seqno {
+                        seqno = pm.uid;
+                    }
+                    l.insert(pm.uid, pm);
+                }
+            },
         }
         let lck = Mutex::new(l);
         let arc = Arc::new(lck);
@@ -65,7 +311,57 @@ impl OpLog {
             seqno: seqno,
             log_arc: arc,
             path: scopy,
-            lf: tlf,
+            lf: Some(tlf),
+            env: None,
+            db: None,
+            format: format,
+            storage: StorageMode::File,
+            write_buf: Vec::new(),
+            pending: 0,
+            batch_size: 1,
+            flush_interval: Duration::from_secs(0),
+            last_flush: Instant::now(),
+        }
+    }
+
+    ///
+    /// from_lmdb_backend(fpath, format)
+    /// Recovery path for the LMDB backend. Restores `seqno` from the last
+    /// committed key without loading the log into memory; records are served
+    /// lazily through the bounded read-through cache, or streamed on demand by
+    /// `stream_records`. LMDB remains the source of truth on disk.
+    ///
+    fn from_lmdb_backend(fpath: String, format: LogFormat) -> OpLog {
+        let (env, db) = open_env(&fpath);
+        let mut seqno = 0;
+        {
+            // Keys are big-endian, so the highest key is the last committed
+            // record. Walk the keys only (values stay on disk) to recover it.
+            let txn = env.begin_ro_txn().unwrap();
+            let mut cursor = txn.open_ro_cursor(db).unwrap();
+            for (key, _value) in cursor.iter() {
+                let uid = key_to_seqno(key);
+                if uid > seqno {
+                    seqno = uid;
+                }
+            }
+        }
+        let lck = Mutex::new(HashMap::new());
+        let arc = Arc::new(lck);
+        OpLog {
+            seqno: seqno,
+            log_arc: arc,
+            path: fpath,
+            lf: None,
+            env: Some(env),
+            db: Some(db),
+            format: format,
+            storage: StorageMode::Lmdb,
+            write_buf: Vec::new(),
+            pending: 0,
+            batch_size: 1,
+            flush_interval: Duration::from_secs(0),
+            last_flush: Instant::now(),
         }
     }
 
@@ -80,10 +376,95 @@ impl OpLog {
         self.seqno += 1;
         let id = self.seqno;
         let pm = message::ProtocolMessage::generate(t, tid, sender, op);
-        serde_json::to_writer(&mut self.lf, &pm).unwrap();
-        writeln!(&mut self.lf).unwrap();
-        self.lf.flush().unwrap();
-        log.insert(id, pm);
+        match self.storage {
+            StorageMode::File => {
+                // Every record carries a CRC32 over its encoded bytes so that a
+                // tail torn by a crash mid-append can be detected and dropped
+                // on recovery instead of being decoded into garbage. Records are
+                // appended to an in-memory buffer and only pushed to stable
+                // storage by `flush_buffer`/`sync`.
+                match self.format {
+                    LogFormat::Json => {
+                        let bytes = serde_json::to_vec(&pm).unwrap();
+                        let crc = crc32fast::hash(&bytes);
+                        self.write_buf.extend_from_slice(&bytes);
+                        self.write_buf.extend_from_slice(format!("\t{}\n", crc).as_bytes());
+                    },
+                    LogFormat::Bincode | LogFormat::Flexbuffers => {
+                        let bytes = encode_record(self.format, &pm);
+                        let crc = crc32fast::hash(&bytes);
+                        self.write_buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                        self.write_buf.extend_from_slice(&bytes);
+                        self.write_buf.extend_from_slice(&crc.to_le_bytes());
+                    },
+                }
+                self.pending += 1;
+                // A zero `flush_interval` disables the time trigger so the
+                // `batch_size` knob governs on its own; otherwise flush once
+                // either the batch is full or the interval has elapsed.
+                let time_trigger = self.flush_interval > Duration::from_secs(0)
+                    && self.last_flush.elapsed() >= self.flush_interval;
+                if self.pending >= self.batch_size || time_trigger {
+                    self.flush_buffer();
+                }
+                // The file backend keeps the whole log resident in the index.
+                log.insert(id, pm);
+            },
+            StorageMode::Lmdb => {
+                // Each record is its own committed transaction, so the log is
+                // crash-consistent at record granularity.
+                let bytes = encode_record(self.format, &pm);
+                let env = self.env.as_ref().unwrap();
+                let db = self.db.unwrap();
+                let mut txn = env.begin_rw_txn().unwrap();
+                txn.put(db, &seqno_to_key(id), &bytes, WriteFlags::empty()).unwrap();
+                txn.commit().unwrap();
+                // The cache is bounded: LMDB holds the durable copy, so evict a
+                // resident entry once the cache is full rather than growing it.
+                cache_insert(&mut log, id, pm);
+            },
+        }
+    }
+
+    ///
+    /// flush_buffer()
+    /// Writes any buffered records to the underlying file and syncs it to
+    /// stable storage, resetting the group-commit counters. No-op unless the
+    /// file backend has pending records.
+    ///
+    fn flush_buffer(&mut self) {
+        if self.pending == 0 {
+            return;
+        }
+        if let Some(lf) = self.lf.as_mut() {
+            lf.write_all(&self.write_buf).unwrap();
+            lf.sync_all().unwrap();
+        }
+        self.write_buf.clear();
+        self.pending = 0;
+        self.last_flush = Instant::now();
+    }
+
+    ///
+    /// sync()
+    ///
+    /// Forces every buffered record to stable storage and blocks until the
+    /// flush completes. 2PC phase boundaries call this before sending a message
+    /// whose recovery depends on the corresponding record being durable (e.g.
+    /// just before a `ParticipantVoteCommit` or `CoordinatorCommit`).
+    ///
+    pub fn sync(&mut self) {
+        match self.storage {
+            StorageMode::File => {
+                self.flush_buffer();
+                if let Some(lf) = self.lf.as_mut() {
+                    lf.sync_all().unwrap();
+                }
+            },
+            // The LMDB backend commits each record in its own transaction, so
+            // records are already durable by the time append returns.
+            StorageMode::Lmdb => {},
+        }
     }
 
     ///
@@ -93,11 +474,50 @@ impl OpLog {
     ///
     pub fn read(&mut self, offset: &u32) -> message::ProtocolMessage {
         let lck = Arc::clone(&self.log_arc);
-        let log = lck.lock().unwrap();
-        let pm = log[&offset].clone();
+        let mut log = lck.lock().unwrap();
+        if let Some(pm) = log.get(offset) {
+            return pm.clone();
+        }
+        // Miss: only the LMDB backend can serve a record that is not resident
+        // in the index, via a point `get`. Fill the index so repeat reads hit.
+        let env = self.env.as_ref().expect("read miss on in-memory backend");
+        let db = self.db.unwrap();
+        let txn = env.begin_ro_txn().unwrap();
+        let bytes = txn.get(db, &seqno_to_key(*offset)).unwrap();
+        let pm = decode_record(self.format, bytes);
+        cache_insert(&mut log, *offset, pm.clone());
         pm
     }
 
+    ///
+    /// stream_records(f)
+    ///
+    /// Invokes `f` on every record in the log without materialising the whole
+    /// log in memory. The file backend replays its resident index; the LMDB
+    /// backend walks a cursor, decoding one record at a time so a log larger
+    /// than RAM can still be checked.
+    ///
+    pub fn stream_records<F: FnMut(&message::ProtocolMessage)>(&self, mut f: F) {
+        match self.storage {
+            StorageMode::File => {
+                let log = self.log_arc.lock().unwrap();
+                for pm in log.values() {
+                    f(pm);
+                }
+            },
+            StorageMode::Lmdb => {
+                let env = self.env.as_ref().unwrap();
+                let db = self.db.unwrap();
+                let txn = env.begin_ro_txn().unwrap();
+                let mut cursor = txn.open_ro_cursor(db).unwrap();
+                for (_key, value) in cursor.iter() {
+                    let pm = decode_record(self.format, value);
+                    f(&pm);
+                }
+            },
+        }
+    }
+
     ///
     /// arc
     ///
@@ -107,3 +527,103 @@ impl OpLog {
         Arc::clone(&self.log_arc)
     }
 }
+
+impl Drop for OpLog {
+    ///
+    /// Flushes any records still buffered by group-commit on teardown, so a
+    /// partial final batch (fewer than `batch_size` records, no terminal
+    /// `sync()`) is not silently lost on a clean process exit. Durability
+    /// against a crash still requires an explicit `sync()`.
+    ///
+    fn drop(&mut self) {
+        self.flush_buffer();
+    }
+}
+
+///
+/// cache_insert(log, id, pm)
+/// Inserts a record into the bounded LMDB read-through cache, evicting an
+/// arbitrary resident entry first when the cache is already at capacity.
+///
+fn cache_insert(log: &mut HashMap<u32, message::ProtocolMessage>, id: u32, pm: message::ProtocolMessage) {
+    if !log.contains_key(&id) && log.len() >= CACHE_CAPACITY {
+        if let Some(evict) = log.keys().next().cloned() {
+            log.remove(&evict);
+        }
+    }
+    log.insert(id, pm);
+}
+
+///
+/// encode_record(format, pm)
+/// Encodes a single `ProtocolMessage` into the payload bytes for a
+/// length-prefixed binary record.
+///
+fn encode_record(format: LogFormat, pm: &message::ProtocolMessage) -> Vec<u8> {
+    match format {
+        LogFormat::Bincode => bincode::serialize(pm).unwrap(),
+        LogFormat::Flexbuffers => flexbuffers::to_vec(pm).unwrap(),
+        LogFormat::Json => serde_json::to_vec(pm).unwrap(),
+    }
+}
+
+///
+/// decode_record(format, buf)
+/// Decodes the payload bytes of a single length-prefixed binary record back
+/// into a `ProtocolMessage`.
+///
+fn decode_record(format: LogFormat, buf: &[u8]) -> message::ProtocolMessage {
+    match format {
+        LogFormat::Bincode => bincode::deserialize(buf).unwrap(),
+        LogFormat::Flexbuffers => flexbuffers::from_slice(buf).unwrap(),
+        LogFormat::Json => serde_json::from_slice(buf).unwrap(),
+    }
+}
+
+///
+/// try_decode_record(format, buf)
+/// Fallible variant of `decode_record` used on the recovery path, where a
+/// record that fails to decode marks the start of a torn tail rather than a
+/// fatal error.
+///
+fn try_decode_record(format: LogFormat, buf: &[u8]) -> Option<message::ProtocolMessage> {
+    match format {
+        LogFormat::Bincode => bincode::deserialize(buf).ok(),
+        LogFormat::Flexbuffers => flexbuffers::from_slice(buf).ok(),
+        LogFormat::Json => serde_json::from_slice(buf).ok(),
+    }
+}
+
+///
+/// open_env(path)
+/// Creates (if necessary) and opens the LMDB environment rooted at `path`,
+/// returning it alongside its unnamed default database.
+///
+fn open_env(path: &str) -> (Environment, Database) {
+    fs::create_dir_all(Path::new(path)).unwrap();
+    let env = Environment::new()
+        .set_map_size(LMDB_MAP_SIZE)
+        .open(Path::new(path))
+        .unwrap();
+    let db = env.open_db(None).unwrap();
+    (env, db)
+}
+
+///
+/// seqno_to_key(seqno)
+/// Encodes a sequence number as a big-endian key so LMDB's lexical ordering
+/// matches numeric ordering and the last key recovers the highest `uid`.
+///
+fn seqno_to_key(seqno: u32) -> [u8; 4] {
+    seqno.to_be_bytes()
+}
+
+///
+/// key_to_seqno(key)
+/// Inverse of `seqno_to_key`.
+///
+fn key_to_seqno(key: &[u8]) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(key);
+    u32::from_be_bytes(buf)
+}
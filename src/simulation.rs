@@ -0,0 +1,315 @@
+//!
+//! simulation.rs
+//! A deterministic fault-injection harness for the _T_wo _P_hase _C_ommit
+//! project. Exports a single public function, `run`, that drives a simulated
+//! 2PC run entirely from a seed: every message is stamped with a delivery tick,
+//! dropped, delayed, or reordered by draws from a seeded PRNG, and participant
+//! crashes are scheduled at PRNG-chosen ticks. Because all nondeterminism flows
+//! from the single seed and the PRNG is advanced in a fixed order independent of
+//! wall-clock timing, any invariant violation found by `checker::check_last_run`
+//! can be replayed bit-for-bit by passing the same `--seed`.
+//!
+extern crate log;
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+use checker;
+use message::MessageType;
+use oplog::OpLog;
+use tpcoptions::TPCOptions;
+
+///
+/// Prng
+/// A tiny xorshift64* generator. It is the sole source of randomness in the
+/// simulation; advancing it in a deterministic order is what makes a failing
+/// schedule replayable.
+///
+struct Prng {
+    state: u64,
+}
+
+impl Prng {
+    ///
+    /// new(seed)
+    /// Seeds the generator, forcing a non-zero state so the xorshift never
+    /// collapses to the fixed point at zero.
+    ///
+    fn new(seed: u64) -> Prng {
+        Prng { state: if seed == 0 { 0x9E3779B97F4A7C15 } else { seed } }
+    }
+
+    ///
+    /// next_u64()
+    /// Advances the state and returns the next 64-bit draw.
+    ///
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    ///
+    /// bernoulli(p)
+    /// Returns true with probability `p`, consuming exactly one draw.
+    ///
+    fn bernoulli(&mut self, p: f64) -> bool {
+        (self.next_u64() as f64) / (u64::max_value() as f64) < p
+    }
+
+    ///
+    /// delay(max)
+    /// Draws an inter-tick delay in `0..=max`, consuming exactly one draw.
+    ///
+    fn delay(&mut self, max: u64) -> u64 {
+        self.next_u64() % (max + 1)
+    }
+}
+
+/// Maximum number of ticks a single message may be delayed by the scheduler.
+const MAX_DELAY: u64 = 4;
+
+/// Ticks the clock advances after each message round. It is deliberately
+/// smaller than `MAX_DELAY + 1` so a message drawn with a large delay lands
+/// past the delivery window and is never delivered to its intended round —
+/// this is what makes the delay fault observable rather than inert.
+const WINDOW: u64 = MAX_DELAY / 2 + 1;
+
+///
+/// Kind
+/// Which protocol message an envelope carries, so a late delivery that slips
+/// into a later round can be recognised as stale and dropped.
+///
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Vote,
+    Decision,
+}
+
+///
+/// Envelope
+/// A scheduled message: the tick it is delivered on, a monotonic sequence used
+/// to break ties deterministically, the transaction and participant it
+/// concerns, its kind, and the commit bit it carries.
+///
+struct Envelope {
+    tick: u64,
+    seq: u64,
+    participant: u32,
+    commit_vote: bool,
+    txid: String,
+    kind: Kind,
+}
+
+impl PartialEq for Envelope {
+    fn eq(&self, other: &Envelope) -> bool {
+        self.tick == other.tick && self.seq == other.seq
+    }
+}
+impl Eq for Envelope {}
+impl Ord for Envelope {
+    fn cmp(&self, other: &Envelope) -> Ordering {
+        // Reverse so the BinaryHeap (a max-heap) pops the earliest tick first,
+        // breaking ties on the monotonic sequence.
+        other.tick.cmp(&self.tick).then(other.seq.cmp(&self.seq))
+    }
+}
+impl PartialOrd for Envelope {
+    fn partial_cmp(&self, other: &Envelope) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+///
+/// Scheduler
+/// Holds the seeded PRNG, the virtual logical clock, and the delivery queue.
+/// Drawing delivery ticks at enqueue time (rather than at delivery) keeps the
+/// draw order independent of how the queue happens to drain.
+///
+struct Scheduler {
+    prng: Prng,
+    clock: u64,
+    seq: u64,
+    queue: BinaryHeap<Envelope>,
+}
+
+impl Scheduler {
+    fn new(seed: u64) -> Scheduler {
+        Scheduler { prng: Prng::new(seed), clock: 0, seq: 0, queue: BinaryHeap::new() }
+    }
+
+    ///
+    /// enqueue(txid, kind, participant, commit_vote, send_success_probability)
+    /// Stamps a message with a deterministically chosen delivery tick, dropping
+    /// it with probability `1.0 - send_success_probability`. The drop draw is
+    /// taken before the delay draw so the order is fixed regardless of outcome.
+    ///
+    fn enqueue(&mut self, txid: &str, kind: Kind, participant: u32, commit_vote: bool, send_success_probability: f64) {
+        let delivered = self.prng.bernoulli(send_success_probability);
+        let delay = self.prng.delay(MAX_DELAY);
+        if !delivered {
+            return;
+        }
+        let tick = self.clock + 1 + delay;
+        self.seq += 1;
+        self.queue.push(Envelope { tick, seq: self.seq, participant, commit_vote, txid: txid.to_string(), kind });
+    }
+
+    ///
+    /// advance(step)
+    /// Moves the virtual logical clock forward by a bounded step, opening the
+    /// delivery window for the next round.
+    ///
+    fn advance(&mut self, step: u64) {
+        self.clock += step;
+    }
+
+    ///
+    /// drain_due()
+    /// Pops and returns only the messages whose delivery tick has been reached
+    /// (`tick <= clock`), in delivery order. Messages stamped for a later tick
+    /// stay queued across rounds, so delay can cause a message to miss its
+    /// round entirely and reorder is visible across transaction boundaries.
+    ///
+    fn drain_due(&mut self) -> Vec<Envelope> {
+        let mut delivered = Vec::new();
+        while let Some(env) = self.queue.peek() {
+            if env.tick > self.clock {
+                break;
+            }
+            delivered.push(self.queue.pop().unwrap());
+        }
+        delivered
+    }
+}
+
+///
+/// run(opts)
+///
+/// Runs a simulated 2PC schedule driven entirely by `opts.random_seed`, writes
+/// coordinator and participant logs under `opts.log_path`, then checks the
+/// recovered logs with `checker::check_last_run`.
+///
+pub fn run(opts: &TPCOptions) {
+    info!("Simulating 2PC run with seed {}", opts.random_seed);
+
+    let mut sched = Scheduler::new(opts.random_seed);
+
+    let flush_interval = Duration::from_millis(opts.flush_interval_ms);
+    let coord_log_path = format!("{}//{}", opts.log_path, "coordinator.log");
+    let mut coord_log = OpLog::new(
+        coord_log_path, opts.log_format, opts.storage_mode, opts.batch_size, flush_interval);
+
+    let mut participant_logs: Vec<OpLog> = (0..opts.num_participants)
+        .map(|pid| {
+            let path = format!("{}//participant_{}.log", opts.log_path, pid);
+            OpLog::new(path, opts.log_format, opts.storage_mode, opts.batch_size, flush_interval)
+        })
+        .collect();
+
+    // Schedule a crash tick for each participant up front, drawing in
+    // participant order so the schedule is stable across replays. Each
+    // participant crashes with probability 0.05 at a tick in `0..=horizon`;
+    // otherwise its crash tick is `u64::max_value()` and it survives the run.
+    let horizon = (opts.num_clients * opts.num_requests * (MAX_DELAY as u32 + 2)).max(1) as u64;
+    let crash_tick: Vec<u64> = (0..opts.num_participants)
+        .map(|_| if sched.prng.bernoulli(0.05) { sched.prng.delay(horizon) } else { u64::max_value() })
+        .collect();
+
+    let mut num_transactions = 0;
+    for client in 0..opts.num_clients {
+        for req in 0..opts.num_requests {
+            num_transactions += 1;
+            let txid = format!("client_{}_tx_{}", client, req);
+
+            // Phase 1: coordinator proposes; each live participant votes and
+            // logs its local vote before the vote message is enqueued.
+            let mut votes_received = 0;
+            let mut all_commit = true;
+            for pid in 0..opts.num_participants {
+                if sched.clock >= crash_tick[pid as usize] {
+                    all_commit = false;
+                    continue;
+                }
+                let commit = sched.prng.bernoulli(opts.operation_success_probability);
+                let mtype = if commit {
+                    MessageType::ParticipantVoteCommit
+                } else {
+                    MessageType::ParticipantVoteAbort
+                };
+                participant_logs[pid as usize].append(
+                    mtype, txid.clone(), format!("participant_{}", pid), req);
+                // The vote record must be durable before the vote is sent so a
+                // crash cannot lose a commit the coordinator relies on.
+                participant_logs[pid as usize].sync();
+                sched.enqueue(&txid, Kind::Vote, pid, commit, opts.send_success_probability);
+            }
+
+            // Advance the clock by one bounded window and deliver only the
+            // votes that arrived in time. A vote delayed past the window (or a
+            // stale message left over from an earlier transaction) does not
+            // count, so the coordinator aborts — which is always safe.
+            sched.advance(WINDOW);
+            for env in sched.drain_due() {
+                if env.txid != txid || env.kind != Kind::Vote {
+                    continue;
+                }
+                votes_received += 1;
+                if !env.commit_vote {
+                    all_commit = false;
+                }
+            }
+
+            // Phase 2: commit only when every participant's commit vote was
+            // received, so a committed transaction always has a local commit
+            // logged by each participant.
+            let commit = all_commit && votes_received == opts.num_participants;
+            let decision = if commit {
+                MessageType::CoordinatorCommit
+            } else {
+                MessageType::CoordinatorAbort
+            };
+            coord_log.append(decision, txid.clone(), "coordinator".to_string(), req);
+            // The decision must be durable before it is broadcast so recovery
+            // after a coordinator crash agrees with what participants saw.
+            coord_log.sync();
+
+            // Broadcast the global decision; participants log it only if the
+            // message arrives within the window and they are still alive.
+            for pid in 0..opts.num_participants {
+                sched.enqueue(&txid, Kind::Decision, pid, commit, opts.send_success_probability);
+            }
+            sched.advance(WINDOW);
+            for env in sched.drain_due() {
+                if env.txid != txid || env.kind != Kind::Decision {
+                    continue;
+                }
+                if sched.clock >= crash_tick[env.participant as usize] {
+                    continue;
+                }
+                participant_logs[env.participant as usize].append(
+                    decision, txid.clone(), format!("participant_{}", env.participant), req);
+            }
+        }
+    }
+
+    // Flush any records still buffered by group-commit before reopening the
+    // logs for checking.
+    coord_log.sync();
+    for log in participant_logs.iter_mut() {
+        log.sync();
+    }
+
+    info!("Simulated {} transactions; checking recovered logs", num_transactions);
+    checker::check_last_run(
+        opts.num_clients,
+        opts.num_requests,
+        opts.num_participants,
+        &opts.log_path,
+        opts.log_format,
+        opts.storage_mode);
+}
@@ -10,9 +10,71 @@
 extern crate log;
 extern crate stderrlog;
 extern crate clap;
+extern crate serde;
+extern crate toml;
+extern crate serde_dhall;
 use clap::{Arg, App};
 
 extern crate ctrlc;
+
+use std::str::FromStr;
+
+use oplog::LogFormat;
+use oplog::StorageMode;
+
+///
+/// FileConfig
+/// A structured config file whose keys map onto the `TPCOptions` fields. Every
+/// field is optional so that a partially-specified file leaves the remaining
+/// fields to the built-in defaults. Loaded from TOML, or from Dhall when the
+/// path ends in `.dhall` for typed/templated configs.
+///
+#[derive(serde::Deserialize, Default)]
+struct FileConfig {
+    send_success_probability: Option<f64>,
+    operation_success_probability: Option<f64>,
+    num_clients: Option<u32>,
+    num_requests: Option<u32>,
+    num_participants: Option<u32>,
+    verbosity: Option<usize>,
+    mode: Option<String>,
+    log_path: Option<String>,
+    ipc_path: Option<String>,
+    num: Option<u32>,
+    log_format: Option<String>,
+    storage_mode: Option<String>,
+    random_seed: Option<u64>,
+    batch_size: Option<usize>,
+    flush_interval_ms: Option<u64>,
+}
+
+///
+/// load_config(path)
+/// Reads a `FileConfig` from disk, dispatching on the file extension: `.dhall`
+/// files are parsed as Dhall, everything else as TOML.
+///
+fn load_config(path: &str) -> FileConfig {
+    if path.ends_with(".dhall") {
+        serde_dhall::from_file(path).parse().unwrap()
+    } else {
+        let contents = std::fs::read_to_string(path).unwrap();
+        toml::from_str(&contents).unwrap()
+    }
+}
+
+///
+/// resolve(cli, file, default)
+/// Applies the configuration precedence for a single field: an explicit CLI
+/// flag wins, then a value from the config file, then the built-in default.
+///
+fn resolve<T: FromStr>(cli: Option<&str>, file: Option<T>, default: T) -> T
+    where T::Err: std::fmt::Debug {
+    match cli {
+        Some(v) => v.parse().unwrap(),
+        None => file.unwrap_or(default),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TPCOptions {
     pub send_success_probability: f64,        // Probability that a message send succeeds
@@ -25,6 +87,11 @@ pub struct TPCOptions {
     pub log_path: String,                     // Directory for client, participant, and coordinator logs
     pub ipc_path: String,                     // Path to IPC socket for setting up communication with the coordinator
     pub num: u32,                             // Participant / Client number for naming the log files
+    pub log_format: LogFormat,                // Wire format used to encode the operations log
+    pub storage_mode: StorageMode,            // Backend used to persist the operations log
+    pub random_seed: u64,                     // Seed driving all nondeterminism in "simulate" mode
+    pub batch_size: usize,                    // Records buffered before a group-commit flush (1 = per-record)
+    pub flush_interval_ms: u64,               // Time after the last flush at which the next append flushes the pending batch (0 = off)
 }
 
 impl TPCOptions {
@@ -46,6 +113,11 @@ impl TPCOptions {
         let default_log_path = "./logs/";
         let default_ipc_path = "none";
         let default_num = "0";
+        let default_log_format = "json";
+        let default_storage_mode = "file";
+        let default_random_seed = "0";
+        let default_batch_size = "1";
+        let default_flush_interval_ms = "0";
 
         // Set-Up clap
         let matches = App::new("concurrency-2pc")
@@ -102,19 +174,60 @@ impl TPCOptions {
                     .required(false)
                     .takes_value(true)
                     .help("Participant / Client number for naming the log files. Ranges from 0 to num_clients - 1 or num_participants - 1"))
+            .arg(Arg::with_name("log_format")
+                    .long("log_format")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Operations log wire format: \"json\" (human readable), \"bincode\", or \"flexbuffers\""))
+            .arg(Arg::with_name("storage_mode")
+                    .long("storage")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Operations log backend: \"file\" (append-only) or \"lmdb\" (durable key/value)"))
+            .arg(Arg::with_name("random_seed")
+                    .long("seed")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Seed driving message loss, delay, reorder, and crashes in \"simulate\" mode; replays identically for the same seed"))
+            .arg(Arg::with_name("batch_size")
+                    .long("batch_size")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Group-commit batch size: records buffered before a durable flush (1 = flush every record)"))
+            .arg(Arg::with_name("flush_interval_ms")
+                    .long("flush_interval")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Milliseconds after the last flush at which the next append flushes the pending group-commit batch (0 disables the time trigger)"))
+            .arg(Arg::with_name("config")
+                    .long("config")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Path to a TOML (or .dhall) config file whose keys map onto these options; explicit CLI flags override file values, which override defaults"))
             .get_matches();
 
-        // Parse CLI options and take default values if none given
-        let mode = matches.value_of("mode").unwrap_or(default_mode);
-        let operation_success_probability = matches.value_of("operation_success_probability").unwrap_or(default_operation_success_probability).parse::<f64>().unwrap();
-        let send_success_probability = matches.value_of("send_success_probability").unwrap_or(default_send_success_probability).parse::<f64>().unwrap();
-        let num_clients = matches.value_of("num_clients").unwrap_or(default_num_clients).parse::<u32>().unwrap();
-        let num_participants = matches.value_of("num_participants").unwrap_or(default_num_participants).parse::<u32>().unwrap();
-        let num_requests = matches.value_of("num_requests").unwrap_or(default_num_requests).parse::<u32>().unwrap();
-        let verbosity = matches.value_of("verbosity").unwrap_or(default_verbosity).parse::<usize>().unwrap();
-        let log_path = matches.value_of("log_path").unwrap_or(default_log_path);
-        let ipc_path = matches.value_of("ipc_path").unwrap_or(default_ipc_path);
-        let num = matches.value_of("num").unwrap_or(default_num).parse::<u32>().unwrap();
+        // Load the optional config file. Precedence for every field is:
+        // explicit CLI flag > config file value > built-in default.
+        let file_cfg = match matches.value_of("config") {
+            Some(path) => load_config(path),
+            None => FileConfig::default(),
+        };
+
+        let mode = resolve(matches.value_of("mode"), file_cfg.mode, default_mode.to_string());
+        let operation_success_probability = resolve(matches.value_of("operation_success_probability"), file_cfg.operation_success_probability, default_operation_success_probability.parse().unwrap());
+        let send_success_probability = resolve(matches.value_of("send_success_probability"), file_cfg.send_success_probability, default_send_success_probability.parse().unwrap());
+        let num_clients = resolve(matches.value_of("num_clients"), file_cfg.num_clients, default_num_clients.parse().unwrap());
+        let num_participants = resolve(matches.value_of("num_participants"), file_cfg.num_participants, default_num_participants.parse().unwrap());
+        let num_requests = resolve(matches.value_of("num_requests"), file_cfg.num_requests, default_num_requests.parse().unwrap());
+        let verbosity = resolve(matches.value_of("verbosity"), file_cfg.verbosity, default_verbosity.parse().unwrap());
+        let log_path = resolve(matches.value_of("log_path"), file_cfg.log_path, default_log_path.to_string());
+        let ipc_path = resolve(matches.value_of("ipc_path"), file_cfg.ipc_path, default_ipc_path.to_string());
+        let num = resolve(matches.value_of("num"), file_cfg.num, default_num.parse().unwrap());
+        let log_format = LogFormat::from_cli(&resolve(matches.value_of("log_format"), file_cfg.log_format, default_log_format.to_string()));
+        let storage_mode = StorageMode::from_cli(&resolve(matches.value_of("storage_mode"), file_cfg.storage_mode, default_storage_mode.to_string()));
+        let random_seed = resolve(matches.value_of("random_seed"), file_cfg.random_seed, default_random_seed.parse().unwrap());
+        let batch_size = resolve(matches.value_of("batch_size"), file_cfg.batch_size, default_batch_size.parse().unwrap());
+        let flush_interval_ms = resolve(matches.value_of("flush_interval_ms"), file_cfg.flush_interval_ms, default_flush_interval_ms.parse().unwrap());
 
         // IPC path is necessary for client / participant to communicate with the coordinator
         match mode.as_ref() {
@@ -130,6 +243,7 @@ impl TPCOptions {
                 }
             },
             "check" => {},
+            "simulate" => {},
             _ => panic!("unknown execution mode requested!"),
         }
 
@@ -144,6 +258,11 @@ impl TPCOptions {
             log_path: log_path.to_string(),
             ipc_path: ipc_path.to_string(),
             num: num,
+            log_format: log_format,
+            storage_mode: storage_mode,
+            random_seed: random_seed,
+            batch_size: batch_size,
+            flush_interval_ms: flush_interval_ms,
         }
     }
 
@@ -164,6 +283,11 @@ impl TPCOptions {
             format!("-l{}", self.log_path),
             format!("--ipc_path={}", self.ipc_path),
             format!("--num={}", self.num),
+            format!("--log_format={}", self.log_format.as_str()),
+            format!("--storage={}", self.storage_mode.as_str()),
+            format!("--seed={}", self.random_seed),
+            format!("--batch_size={}", self.batch_size),
+            format!("--flush_interval={}", self.flush_interval_ms),
         ]
     }
 }